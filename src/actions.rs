@@ -0,0 +1,176 @@
+use clap::ValueEnum;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// What to do with the non-kept members of a duplicate group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DupAction {
+  /// Unlink every duplicate, keeping only the chosen original.
+  Delete,
+  /// Replace every duplicate with a hard link to the chosen original.
+  Hardlink,
+  /// Replace every duplicate with a symlink to the chosen original.
+  Symlink,
+}
+
+/// Which member of a duplicate group to treat as the original to keep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum KeepPolicy {
+  /// Keep whichever path comes first in the group's list. That list is in the order the hashing stages produced the paths, which is not necessarily the order the walk discovered them.
+  #[default]
+  First,
+  /// Keep the path with the shortest string representation.
+  ShortestPath,
+  /// Keep the path with the oldest modified time.
+  OldestMtime,
+}
+
+/// The outcome of acting on a single duplicate group.
+pub struct GroupResult {
+  pub kept: PathBuf,
+  pub replaced: Vec<PathBuf>,
+  pub error: Option<String>,
+}
+
+fn choose_keeper(paths: &[PathBuf], policy: KeepPolicy) -> Result<usize, String> {
+  match policy {
+    KeepPolicy::First => Ok(0),
+    KeepPolicy::ShortestPath => Ok(
+      paths
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, path)| path.as_os_str().len())
+        .map(|(i, _)| i)
+        .unwrap(),
+    ),
+    KeepPolicy::OldestMtime => {
+      let mut best: Option<(usize, SystemTime)> = None;
+      for (i, path) in paths.iter().enumerate() {
+        let mtime = fs::metadata(path)
+          .and_then(|meta| meta.modified())
+          .map_err(|err| format!("failed to stat {:?}: {}", path, err))?;
+        if best.is_none_or(|(_, best_mtime)| mtime < best_mtime) {
+          best = Some((i, mtime));
+        };
+      }
+      Ok(best.map(|(i, _)| i).unwrap())
+    }
+  }
+}
+
+/// The temporary name used to stage `path`'s replacement (or, for `Delete`, to stage `path` itself) in the same directory, so the real swap is a single same-filesystem rename/unlink rather than a window where `path` is missing or half-written.
+fn tmp_path_for(path: &Path) -> PathBuf {
+  path.with_file_name(format!(
+    ".{}.treeutils-tmp-{}",
+    path.file_name().unwrap_or_default().to_string_lossy(),
+    std::process::id()
+  ))
+}
+
+/// Phase 1 of acting on one group member: stage the change at a temporary path without touching `path` itself, so a failure here leaves `path` completely untouched. For `Delete`, "staging" means renaming `path` out of the way (undoable by renaming it back); for `Hardlink`/`Symlink`, it means creating the replacement link under the temporary name.
+fn prepare(kept: &Path, path: &Path, action: DupAction) -> Result<PathBuf, String> {
+  let tmp = tmp_path_for(path);
+  match action {
+    DupAction::Delete => fs::rename(path, &tmp).map_err(|err| format!("failed to stage delete: {}", err))?,
+    DupAction::Hardlink => fs::hard_link(kept, &tmp).map_err(|err| format!("failed to create link: {}", err))?,
+    DupAction::Symlink => {
+      // `kept` is whatever path the caller happened to discover it at, which may be relative to the current directory rather than to the link we're about to create. Canonicalize it so the symlink resolves regardless of where it ends up or what the current directory is later.
+      let target = kept
+        .canonicalize()
+        .map_err(|err| format!("failed to resolve link target: {}", err))?;
+      symlink(target, &tmp).map_err(|err| format!("failed to create link: {}", err))?
+    }
+  };
+  Ok(tmp)
+}
+
+/// Phase 2 of acting on one group member, run only once every member in the group has successfully completed phase 1: make the staged change permanent. For `Delete` this is the point the original content is actually gone; for `Hardlink`/`Symlink` it's an atomic rename of the prepared link over `path`.
+fn commit(tmp: &Path, path: &Path, action: DupAction) -> Result<(), String> {
+  match action {
+    DupAction::Delete => fs::remove_file(tmp).map_err(|err| format!("failed to delete: {}", err)),
+    DupAction::Hardlink | DupAction::Symlink => fs::rename(tmp, path)
+      .map_err(|err| format!("failed to move link into place: {}", err)),
+  }
+}
+
+/// Undoes a successful phase 1 for a member that's being abandoned because a sibling in the same group failed its own phase 1.
+fn rollback(tmp: &Path, path: &Path, action: DupAction) {
+  match action {
+    DupAction::Delete => {
+      let _ = fs::rename(tmp, path);
+    }
+    DupAction::Hardlink | DupAction::Symlink => {
+      let _ = fs::remove_file(tmp);
+    }
+  };
+}
+
+/// Keeps one member of `group` (chosen by `keep_policy`) and applies `action` to the rest. If `dry_run` is set, nothing on disk is touched and the result reflects what would have happened.
+///
+/// Every member is first staged (phase 1) without touching its real path; only once every member has staged successfully are the staged changes committed (phase 2). If any member fails to stage, every already-staged member is rolled back and the whole group is skipped untouched, so a single bad member (permission denied, cross-device link, etc.) can't leave the group half-converted. Phase 2 itself is reduced to a same-directory rename or unlink of a file we just created/moved, so in practice it doesn't fail; if it somehow does anyway, the error is still recorded and the members committed so far are not rolled back, since by that point they're no longer reversible (this is the one case where the group can end up partially converted).
+pub fn act_on_group(
+  group: &[PathBuf],
+  action: DupAction,
+  keep_policy: KeepPolicy,
+  dry_run: bool,
+) -> GroupResult {
+  let kept = match choose_keeper(group, keep_policy) {
+    Ok(i) => group[i].clone(),
+    Err(err) => {
+      return GroupResult {
+        kept: group[0].clone(),
+        replaced: vec![],
+        error: Some(err),
+      };
+    }
+  };
+
+  let targets: Vec<&PathBuf> = group.iter().filter(|path| **path != kept).collect();
+  if dry_run {
+    return GroupResult {
+      kept,
+      replaced: targets.into_iter().cloned().collect(),
+      error: None,
+    };
+  };
+
+  // Phase 1: stage every member before committing any of them.
+  let mut staged = Vec::new();
+  for path in targets {
+    match prepare(&kept, path, action) {
+      Ok(tmp) => staged.push((path.clone(), tmp)),
+      Err(err) => {
+        for (path, tmp) in &staged {
+          rollback(tmp, path, action);
+        }
+        return GroupResult {
+          kept,
+          replaced: vec![],
+          error: Some(format!("{:?}: {}", path, err)),
+        };
+      }
+    };
+  }
+
+  // Phase 2: every member staged successfully, so commit them all.
+  let mut replaced = Vec::new();
+  for (path, tmp) in staged {
+    if let Err(err) = commit(&tmp, &path, action) {
+      return GroupResult {
+        kept,
+        replaced,
+        error: Some(format!("{:?}: {}", path, err)),
+      };
+    };
+    replaced.push(path);
+  }
+
+  GroupResult {
+    kept,
+    replaced,
+    error: None,
+  }
+}
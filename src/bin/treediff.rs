@@ -8,7 +8,10 @@ use std::cmp::min;
 use std::cmp::Reverse;
 use std::collections::BTreeMap;
 use std::path::PathBuf;
+use std::sync::Arc;
 use treeutils::hash_files_in_trees;
+use treeutils::FilterArgs;
+use treeutils::HashType;
 
 #[derive(Debug, Parser)]
 #[command(author, version, about)]
@@ -22,6 +25,17 @@ struct Cli {
   /// Use relative paths for detected copies.
   #[arg(long, default_value_t = false)]
   relative_copy_paths: bool,
+
+  /// Hash algorithm used to compare file contents.
+  #[arg(long, value_enum, default_value_t = HashType::Blake3)]
+  algorithm: HashType,
+
+  /// Cache file to store file hashes in, keyed by path, size, and modified time, so unchanged files don't need to be re-hashed on the next run. This is the biggest win for treediff, since every invocation otherwise re-hashes both trees in full.
+  #[arg(long)]
+  cache: Option<PathBuf>,
+
+  #[command(flatten)]
+  filters: FilterArgs,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -58,7 +72,14 @@ async fn main() {
   if old_base.starts_with(&new_base) || new_base.starts_with(&old_base) {
     panic!("old and new directories overlap");
   };
-  let hashes = hash_files_in_trees(&[&old_base, &new_base]).await;
+  let filters = Arc::new(cli.filters.build().expect("invalid --ignore pattern"));
+  let hashes = hash_files_in_trees(
+    &[&old_base, &new_base],
+    cli.algorithm,
+    cli.cache.as_deref(),
+    filters,
+  )
+  .await;
 
   // Copies/renames are separate to diffs. We always show files as being added, changed, or removed. However, for files where we think they were renamed or copied because there are identical files in the new dir, we list them alongside the old path diff listing entry as a hint. A rename is simply a copy where the old path is also deleted. One old path could be copied to multiple new paths, but a new path can only ever be associated with one old path.
   let mut copies_from = FxHashMap::default();
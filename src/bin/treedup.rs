@@ -1,7 +1,14 @@
 use clap::Parser;
 use colored::Colorize;
 use std::path::PathBuf;
+use std::sync::Arc;
+use treeutils::act_on_group;
 use treeutils::hash_files_in_trees;
+use treeutils::DupAction;
+use treeutils::FilterArgs;
+use treeutils::HashType;
+use treeutils::Hashes;
+use treeutils::KeepPolicy;
 
 #[derive(Debug, Parser)]
 #[command(author, version, about)]
@@ -12,12 +19,65 @@ struct Cli {
   /// Do not print formatted output.
   #[arg(long, default_value_t = false)]
   raw: bool,
+
+  /// Hash algorithm used to compare file contents. xxh3 and crc32 are faster but not collision-resistant, so they're refused together with --delete/--hardlink/--symlink.
+  #[arg(long, value_enum, default_value_t = HashType::Blake3)]
+  algorithm: HashType,
+
+  /// Cache file to store file hashes in, keyed by path, size, and modified time, so unchanged files don't need to be re-hashed on the next run.
+  #[arg(long)]
+  cache: Option<PathBuf>,
+
+  #[command(flatten)]
+  filters: FilterArgs,
+
+  /// Delete every duplicate in a group, keeping one original.
+  #[arg(long, conflicts_with_all = ["hardlink", "symlink"])]
+  delete: bool,
+
+  /// Replace every duplicate in a group with a hard link to one kept original.
+  #[arg(long, conflicts_with_all = ["delete", "symlink"])]
+  hardlink: bool,
+
+  /// Replace every duplicate in a group with a symlink to one kept original.
+  #[arg(long, conflicts_with_all = ["delete", "hardlink"])]
+  symlink: bool,
+
+  /// Which member of a duplicate group to keep as the original.
+  #[arg(long, value_enum, default_value_t = KeepPolicy::First)]
+  keep: KeepPolicy,
+
+  /// Report what --delete/--hardlink/--symlink would do without changing anything on disk.
+  #[arg(long, default_value_t = false)]
+  dry_run: bool,
 }
 
 #[tokio::main]
 async fn main() {
   let cli = Cli::parse();
-  let hashes = hash_files_in_trees(&[&cli.root]).await;
+
+  let action = if cli.delete {
+    Some(DupAction::Delete)
+  } else if cli.hardlink {
+    Some(DupAction::Hardlink)
+  } else if cli.symlink {
+    Some(DupAction::Symlink)
+  } else {
+    None
+  };
+  if action.is_some() && cli.algorithm != HashType::Blake3 {
+    // xxh3 and crc32 trade away collision resistance for speed, which is a fine trade for a read-only report (a human reviews the groups before deleting anything), but not for an action that mutates the tree on our own say-so: a hash collision between genuinely distinct files would delete or overwrite one of them.
+    panic!("--delete/--hardlink/--symlink require --algorithm blake3 (the default); xxh3/crc32 collide too easily to trust with a mutating action");
+  };
+
+  let filters = Arc::new(cli.filters.build().expect("invalid --ignore pattern"));
+  let hashes =
+    hash_files_in_trees(&[&cli.root], cli.algorithm, cli.cache.as_deref(), filters).await;
+
+  if let Some(action) = action {
+    act_on_duplicates(&hashes, action, cli.keep, cli.dry_run);
+    return;
+  };
 
   let mut dup = false;
   for e in hashes.iter() {
@@ -49,3 +109,43 @@ async fn main() {
     println!("{}", "No duplicates found".bright_green());
   };
 }
+
+fn act_on_duplicates(
+  hashes: &Hashes,
+  action: DupAction,
+  keep_policy: KeepPolicy,
+  dry_run: bool,
+) {
+  let (verb, past_tense) = match action {
+    DupAction::Delete => ("delete", "Deleted"),
+    DupAction::Hardlink => ("hardlink", "Hardlinked"),
+    DupAction::Symlink => ("symlink", "Symlinked"),
+  };
+  let prefix = if dry_run {
+    format!("Would {verb}:")
+  } else {
+    format!("{past_tense}:")
+  };
+
+  let mut acted = false;
+  for e in hashes.iter() {
+    let paths = e.value();
+    if paths.len() <= 1 {
+      continue;
+    };
+    acted = true;
+    let result = act_on_group(paths, action, keep_policy, dry_run);
+    println!("{}", format!("{:?}", result.kept).bold());
+    for path in &result.replaced {
+      println!("├ {} {}", prefix, format!("{:?}", path).bright_blue());
+    }
+    if let Some(err) = &result.error {
+      println!("{} {}", "⚠️ group skipped:".bright_red(), err);
+    };
+    println!("");
+  }
+
+  if !acted {
+    println!("{}", "No duplicates found".bright_green());
+  };
+}
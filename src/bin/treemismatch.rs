@@ -0,0 +1,96 @@
+use clap::Parser;
+use colored::Colorize;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use treeutils::list_files_in_trees;
+use treeutils::FilterArgs;
+
+#[derive(Debug, Parser)]
+#[command(author, version, about)]
+struct Cli {
+  /// Root directory.
+  root: PathBuf,
+
+  #[command(flatten)]
+  filters: FilterArgs,
+}
+
+// Extensions whose content is interchangeable for this check's purposes, so a file isn't flagged just for using one of several equally-valid spellings of the same format.
+const EXTENSION_ALIASES: &[&[&str]] = &[
+  &["jpg", "jpeg"],
+  &["htm", "html"],
+  &["tif", "tiff"],
+  &["tar", "gz", "tgz", "gzip"],
+];
+
+fn acceptable_extensions(canonical: &str) -> Vec<&'static str> {
+  for group in EXTENSION_ALIASES {
+    if group.contains(&canonical) {
+      return group.to_vec();
+    };
+  }
+  vec![]
+}
+
+/// Sniffs the leading bytes of `path` and, if its content type disagrees with its extension, returns the detected extension and the set of extensions that would have been accepted.
+fn check_mismatch(path: &Path) -> Result<Option<(String, Vec<&'static str>)>, String> {
+  let mut file = File::open(path).map_err(|err| format!("failed to open file: {}", err))?;
+  let mut buf = vec![0u8; 8192];
+  let n = file
+    .read(&mut buf)
+    .map_err(|err| format!("failed to read file: {}", err))?;
+  buf.truncate(n);
+
+  let Some(kind) = infer::get(&buf) else {
+    // Unrecognized content (plain text, unknown format, etc.) - nothing to compare against.
+    return Ok(None);
+  };
+
+  let actual_ext = path
+    .extension()
+    .map(|ext| ext.to_string_lossy().to_lowercase())
+    .unwrap_or_default();
+  let detected_ext = kind.extension();
+  let mut expected = acceptable_extensions(detected_ext);
+  if expected.is_empty() {
+    expected.push(detected_ext);
+  };
+  if expected.contains(&actual_ext.as_str()) {
+    return Ok(None);
+  };
+
+  Ok(Some((detected_ext.to_string(), expected)))
+}
+
+#[tokio::main]
+async fn main() {
+  let cli = Cli::parse();
+  let filters = Arc::new(cli.filters.build().expect("invalid --ignore pattern"));
+  let paths = list_files_in_trees(&[&cli.root], filters).await;
+
+  let mut mismatches = false;
+  for path in &paths {
+    match check_mismatch(path) {
+      Ok(Some((found, expected))) => {
+        mismatches = true;
+        println!(
+          "{} found {}, expected one of {:?}",
+          format!("{:?}", path).bold(),
+          found.bright_yellow(),
+          expected
+        );
+      }
+      Ok(None) => {}
+      Err(err) => {
+        println!("{} {:?}: {}", "⚠️".bright_red(), path, err);
+      }
+    };
+  }
+
+  if !mismatches {
+    println!("{}", "No mismatched extensions found".bright_green());
+  };
+}
@@ -0,0 +1,80 @@
+use crate::HashType;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+  size: u64,
+  mtime_nanos: i64,
+  hash_type: HashType,
+  hash: Vec<u8>,
+}
+
+/// An opt-in on-disk cache of full file hashes, keyed by canonical path, so repeated runs over an unchanged tree don't need to re-read every file. Stale entries (changed size/mtime/algorithm) are never served; on flush, entries whose path no longer exists are dropped, while other stale entries are kept until their file is fully hashed again.
+pub struct HashCache {
+  path: PathBuf,
+  entries: Mutex<HashMap<PathBuf, CacheEntry>>,
+}
+
+impl HashCache {
+  pub fn load(path: PathBuf) -> Self {
+    let entries = File::open(&path)
+      .ok()
+      .and_then(|file| bincode::deserialize_from(BufReader::new(file)).ok())
+      .unwrap_or_default();
+    Self {
+      path,
+      entries: Mutex::new(entries),
+    }
+  }
+
+  /// Returns the cached hash for `canonical_path` if it's still fresh (same size, mtime, and algorithm as when it was recorded).
+  pub fn get(
+    &self,
+    canonical_path: &Path,
+    size: u64,
+    mtime_nanos: i64,
+    hash_type: HashType,
+  ) -> Option<Vec<u8>> {
+    let entries = self.entries.lock().unwrap();
+    let entry = entries.get(canonical_path)?;
+    (entry.size == size && entry.mtime_nanos == mtime_nanos && entry.hash_type == hash_type)
+      .then(|| entry.hash.clone())
+  }
+
+  pub fn put(
+    &self,
+    canonical_path: PathBuf,
+    size: u64,
+    mtime_nanos: i64,
+    hash_type: HashType,
+    hash: Vec<u8>,
+  ) {
+    self.entries.lock().unwrap().insert(
+      canonical_path,
+      CacheEntry {
+        size,
+        mtime_nanos,
+        hash_type,
+        hash,
+      },
+    );
+  }
+
+  /// Flushes the merged cache back to disk, pruning any entry whose path no longer exists.
+  pub fn flush(self) {
+    let mut entries = self.entries.into_inner().unwrap();
+    entries.retain(|path, _| path.exists());
+    let Ok(file) = File::create(&self.path) else {
+      return;
+    };
+    let _ = bincode::serialize_into(BufWriter::new(file), &entries);
+  }
+}
@@ -0,0 +1,115 @@
+use clap::Args;
+use globset::Glob;
+use globset::GlobSet;
+use globset::GlobSetBuilder;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Flags shared by every binary that walks a tree with `hash_files_in_trees`, so they can all be filtered the same way. Flatten this into a binary's `Cli` with `#[command(flatten)]`.
+#[derive(Debug, Args)]
+pub struct FilterArgs {
+  /// Glob pattern to exclude from traversal (e.g. `.git`, `node_modules`, `*.tmp`). A pattern with no `/` matches at any depth, like in a `.gitignore`. Can be given multiple times.
+  #[arg(long = "ignore")]
+  pub ignore: Vec<String>,
+
+  /// Only consider files with one of these extensions (case-insensitive). Can be given multiple times.
+  #[arg(long = "ext")]
+  pub include_extensions: Vec<String>,
+
+  /// Skip files with one of these extensions (case-insensitive). Can be given multiple times.
+  #[arg(long = "exclude-ext")]
+  pub exclude_extensions: Vec<String>,
+
+  /// Skip files smaller than this many bytes.
+  #[arg(long)]
+  pub min_size: Option<u64>,
+
+  /// Skip files larger than this many bytes.
+  #[arg(long)]
+  pub max_size: Option<u64>,
+}
+
+impl FilterArgs {
+  pub fn build(&self) -> Result<FileFilters, globset::Error> {
+    FileFilters::build(
+      &self.ignore,
+      &self.include_extensions,
+      &self.exclude_extensions,
+      self.min_size,
+      self.max_size,
+    )
+  }
+}
+
+/// Traversal-time filters that `visit_file`/`visit_dir` consult before a path is enqueued for hashing (or descended into, for directories), so unwanted paths never cost more than the one `stat` needed to check them.
+pub struct FileFilters {
+  ignore: GlobSet,
+  include_extensions: HashSet<String>,
+  exclude_extensions: HashSet<String>,
+  min_size: Option<u64>,
+  max_size: Option<u64>,
+}
+
+impl FileFilters {
+  pub fn build(
+    ignore_patterns: &[String],
+    include_extensions: &[String],
+    exclude_extensions: &[String],
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+  ) -> Result<Self, globset::Error> {
+    let mut ignore = GlobSetBuilder::new();
+    for pattern in ignore_patterns {
+      ignore.add(Glob::new(pattern)?);
+      if !pattern.contains('/') {
+        // A bare name, as in `.gitignore`, should match at any depth, not just relative to the tree root.
+        ignore.add(Glob::new(&format!("**/{pattern}"))?);
+      };
+    }
+    Ok(Self {
+      ignore: ignore.build()?,
+      include_extensions: include_extensions
+        .iter()
+        .map(|ext| ext.to_lowercase())
+        .collect(),
+      exclude_extensions: exclude_extensions
+        .iter()
+        .map(|ext| ext.to_lowercase())
+        .collect(),
+      min_size,
+      max_size,
+    })
+  }
+
+  /// Whether `path` (file or directory) should be skipped entirely. Checking this before descending into a directory prunes whole subtrees without statting anything inside them.
+  pub fn is_ignored(&self, path: &Path) -> bool {
+    self.ignore.is_match(path)
+  }
+
+  /// Whether a file of the given size should be enqueued for hashing, based on its extension and size bounds.
+  pub fn accepts_file(&self, path: &Path, size: u64) -> bool {
+    if self.min_size.is_some_and(|min| size < min) {
+      return false;
+    };
+    if self.max_size.is_some_and(|max| size > max) {
+      return false;
+    };
+    let ext = path
+      .extension()
+      .map(|ext| ext.to_string_lossy().to_lowercase())
+      .unwrap_or_default();
+    if !self.include_extensions.is_empty() && !self.include_extensions.contains(&ext) {
+      return false;
+    };
+    if self.exclude_extensions.contains(&ext) {
+      return false;
+    };
+    true
+  }
+}
+
+impl Default for FileFilters {
+  fn default() -> Self {
+    Self::build(&[], &[], &[], None, None).unwrap()
+  }
+}
@@ -1,9 +1,21 @@
+mod actions;
+mod cache;
+mod filters;
+
+pub use actions::act_on_group;
+pub use actions::DupAction;
+pub use actions::GroupResult;
+pub use actions::KeepPolicy;
 use async_recursion::async_recursion;
-use blake3::Hasher;
+use cache::HashCache;
 use clap::Parser;
+use clap::ValueEnum;
 use crossbeam::channel::unbounded;
 use crossbeam::channel::Sender;
+use crc32fast::Hasher as Crc32Hasher;
 use dashmap::DashMap;
+pub use filters::FileFilters;
+pub use filters::FilterArgs;
 use futures::stream::iter;
 use futures::StreamExt;
 use indicatif::MultiProgress;
@@ -11,6 +23,10 @@ use indicatif::ProgressBar;
 use indicatif::ProgressStyle;
 use itertools::Itertools;
 use rustc_hash::FxHasher;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fs::File;
 use std::hash::BuildHasherDefault;
 use std::io::Read;
@@ -18,14 +34,33 @@ use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::thread::spawn;
+use std::time::UNIX_EPOCH;
 use terminal_size::terminal_size;
 use terminal_size::Width;
+use tokio::fs::canonicalize;
 use tokio::fs::read_dir;
 use tokio::fs::symlink_metadata;
+use xxhash_rust::xxh3::Xxh3;
 
 // We use async to iterate the file system tree and build the progress bar, and a sync thread pool to do the actual hashing. We don't want to hash within async, as it'll block the progress bar building, and we don't want to use spawn_blocking as it'll run too many threads. We use async to build the tree as it's faster than sync, even with multiple threads.
 
-type Hashes = Arc<DashMap<Vec<u8>, Vec<PathBuf>, BuildHasherDefault<FxHasher>>>;
+pub type Hashes = Arc<DashMap<Vec<u8>, Vec<PathBuf>, BuildHasherDefault<FxHasher>>>;
+
+// (path, mtime_nanos, canonical path if a cache is in use, cached hash if this path was already a cache hit).
+type FileInfo = (PathBuf, i64, Option<PathBuf>, Option<Vec<u8>>);
+
+// (path, mtime_nanos, canonical path if a cache is in use). Only ever holds files still awaiting a hash (a cache hit or a unique size has already resolved everyone else).
+type UnhashedInfo = (PathBuf, i64, Option<PathBuf>);
+
+type SizeGroups = HashMap<u64, Vec<FileInfo>, BuildHasherDefault<FxHasher>>;
+
+// Only the first `PARTIAL_HASH_BYTES` of a file are read for the stage 2 partial hash. This is enough to rule out the vast majority of same-sized-but-different files without paying for a full read.
+const PARTIAL_HASH_BYTES: usize = 16 * 1024;
+
+// `Hashes` keys are tagged so that the cheap synthetic keys we use for files we never actually fully hash (because we already know they're unique) can never collide with a real BLAKE3 digest, regardless of key length.
+const KEY_TAG_UNIQUE_SIZE: u8 = 0;
+const KEY_TAG_UNIQUE_PARTIAL: u8 = 1;
+const KEY_TAG_FULL_HASH: u8 = 2;
 
 #[derive(Debug, Parser)]
 #[command(author, version, about)]
@@ -38,17 +73,88 @@ struct Cli {
   raw: bool,
 }
 
+/// Hash algorithm used to fingerprint file contents. BLAKE3 is cryptographic and the default; xxh3 and crc32 are much faster non-cryptographic alternatives for when tamper resistance doesn't matter, only dedup detection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+pub enum HashType {
+  #[default]
+  Blake3,
+  Xxh3,
+  Crc32,
+}
+
+/// A streaming hasher that can be boxed so `process_file_partial`/`process_file_full` don't need to be generic over the concrete algorithm.
+trait TreeHasher: Send {
+  fn update(&mut self, buf: &[u8]);
+  fn finish(self: Box<Self>) -> Vec<u8>;
+}
+
+impl TreeHasher for blake3::Hasher {
+  fn update(&mut self, buf: &[u8]) {
+    blake3::Hasher::update(self, buf);
+  }
+
+  fn finish(self: Box<Self>) -> Vec<u8> {
+    self.finalize().as_bytes().to_vec()
+  }
+}
+
+impl TreeHasher for Xxh3 {
+  fn update(&mut self, buf: &[u8]) {
+    Xxh3::update(self, buf);
+  }
+
+  fn finish(self: Box<Self>) -> Vec<u8> {
+    self.digest().to_le_bytes().to_vec()
+  }
+}
+
+impl TreeHasher for Crc32Hasher {
+  fn update(&mut self, buf: &[u8]) {
+    Crc32Hasher::update(self, buf);
+  }
+
+  fn finish(self: Box<Self>) -> Vec<u8> {
+    self.finalize().to_le_bytes().to_vec()
+  }
+}
+
+fn new_hasher(hash_type: HashType) -> Box<dyn TreeHasher> {
+  match hash_type {
+    HashType::Blake3 => Box::new(blake3::Hasher::new()),
+    HashType::Xxh3 => Box::new(Xxh3::new()),
+    HashType::Crc32 => Box::new(Crc32Hasher::new()),
+  }
+}
+
 #[derive(Clone)]
 struct Ctx {
   mp: MultiProgress,
-  pb: ProgressBar,
-  // (path, size). Use size for more accurate progress indication.
-  sender: Sender<(PathBuf, u64)>,
+  // (path, size, mtime_nanos, canonical path if a cache is in use, cached hash if this path was already a cache hit).
+  sender: Sender<(PathBuf, u64, i64, Option<PathBuf>, Option<Vec<u8>>)>,
+  cache: Option<Arc<HashCache>>,
+  hash_type: HashType,
+  filters: Arc<FileFilters>,
+}
+
+fn mtime_nanos(meta: &std::fs::Metadata) -> Result<i64, String> {
+  let modified = meta
+    .modified()
+    .map_err(|err| format!("failed to read modified time: {}", err))?;
+  Ok(
+    modified
+      .duration_since(UNIX_EPOCH)
+      .map_err(|err| format!("file modified time is before the epoch: {}", err))?
+      .as_nanos() as i64,
+  )
 }
 
 #[async_recursion]
 async fn visit_file(ctx: &Ctx, path: &Path) -> Result<(), String> {
   // Symlinks, if they resolve, will obviously be a duplicate. Also, multiple symlinks to the same file doesn't really mean anything. Therefore, lstat and ignore symlinks.
+  if ctx.filters.is_ignored(path) {
+    // Pruned: for a directory this skips the whole subtree without statting anything inside it.
+    return Ok(());
+  };
   let meta = symlink_metadata(path)
     .await
     .map_err(|err| format!("failed to stat file: {}", err))?;
@@ -62,10 +168,34 @@ async fn visit_file(ctx: &Ctx, path: &Path) -> Result<(), String> {
     return Ok(());
   };
   let size = meta.len();
-  if size != 0 {
-    ctx.pb.inc_length(size);
-    ctx.sender.send((path.to_path_buf(), size)).unwrap();
+  if size == 0 || !ctx.filters.accepts_file(path, size) {
+    return Ok(());
+  };
+  let mtime_nanos = mtime_nanos(&meta)?;
+
+  // A cache hit already tells us this file's real content hash, but it must still be counted in stage 1's size grouping: another, uncached file of the same size is only safe to call unique if every same-sized file (cached or not) was considered.
+  if let Some(cache) = &ctx.cache {
+    let canonical_path = canonicalize(path)
+      .await
+      .map_err(|err| format!("failed to canonicalize path: {}", err))?;
+    let cached_hash = cache.get(&canonical_path, size, mtime_nanos, ctx.hash_type);
+    ctx
+      .sender
+      .send((
+        path.to_path_buf(),
+        size,
+        mtime_nanos,
+        Some(canonical_path),
+        cached_hash,
+      ))
+      .unwrap();
+    return Ok(());
   };
+
+  ctx
+    .sender
+    .send((path.to_path_buf(), size, mtime_nanos, None, None))
+    .unwrap();
   Ok(())
 }
 
@@ -90,8 +220,125 @@ async fn visit_dir(ctx: &Ctx, dir: &Path) -> Result<(), String> {
   Ok(())
 }
 
-fn process_file(hashes: Hashes, path: &Path) -> Result<(), String> {
-  let mut hasher = Hasher::new();
+#[derive(Clone)]
+struct WalkCtx {
+  mp: MultiProgress,
+  sender: Sender<PathBuf>,
+  filters: Arc<FileFilters>,
+}
+
+// A leaner counterpart to `visit_file`/`visit_dir` for consumers that only need the list of files a tree contains, not their hashes: no cache lookups, no channel payload beyond the path itself, and no stage to ultimately feed into `hash_files_in_trees`'s grouping.
+#[async_recursion]
+async fn walk_file(ctx: &WalkCtx, path: &Path) -> Result<(), String> {
+  if ctx.filters.is_ignored(path) {
+    return Ok(());
+  };
+  let meta = symlink_metadata(path)
+    .await
+    .map_err(|err| format!("failed to stat file: {}", err))?;
+  if meta.is_dir() {
+    if let Err(err) = walk_dir(ctx, path).await {
+      ctx.mp.println(format!("⚠️ [{:?}] {}", path, err)).unwrap();
+      return Ok(());
+    };
+  };
+  if !meta.is_file() {
+    return Ok(());
+  };
+  let size = meta.len();
+  if size == 0 || !ctx.filters.accepts_file(path, size) {
+    return Ok(());
+  };
+  ctx.sender.send(path.to_path_buf()).unwrap();
+  Ok(())
+}
+
+#[async_recursion]
+async fn walk_dir(ctx: &WalkCtx, dir: &Path) -> Result<(), String> {
+  let mut it = read_dir(&dir)
+    .await
+    .map_err(|err| format!("failed to read folder: {err}"))?;
+  while let Some(e) = it
+    .next_entry()
+    .await
+    .map_err(|err| format!("failed to iterate folder: {err}"))?
+  {
+    if let Err(err) = walk_file(ctx, &e.path()).await {
+      ctx
+        .mp
+        .println(format!("⚠️ [{:?}] {}", e.path(), err))
+        .unwrap();
+      // Keep processing remaining files.
+    };
+  }
+  Ok(())
+}
+
+/// Walks `folder_paths`, applying `filters`, and returns every accepted file path. Unlike `hash_files_in_trees`, nothing is hashed or grouped, so this is the right entry point for consumers (like treemismatch) that only care about which files exist, not whether any are duplicates.
+pub async fn list_files_in_trees(folder_paths: &[&Path], filters: Arc<FileFilters>) -> Vec<PathBuf> {
+  let mp = MultiProgress::new();
+  let (sender, receiver) = unbounded::<PathBuf>();
+  let ctx = WalkCtx {
+    mp: mp.clone(),
+    sender,
+    filters,
+  };
+
+  iter(folder_paths)
+    .for_each_concurrent(None, |folder_path| {
+      let ctx = ctx.clone();
+      let mp = mp.clone();
+      async move {
+        if let Err(err) = walk_dir(&ctx, folder_path).await {
+          mp.println(format!("⚠️ [{:?}] {}", folder_path, err))
+            .unwrap();
+        };
+      }
+    })
+    .await;
+  drop(ctx);
+
+  receiver.try_iter().collect()
+}
+
+// Shortens `msg` to fit the terminal so the spinner line doesn't wrap, keeping a prefix and suffix with a `…` in between.
+fn fit_to_terminal(msg: String, term_width: u16) -> String {
+  let raw = msg.chars().collect_vec();
+  // TODO Handle underflow.
+  let max_len = usize::from(term_width) - 15;
+  if raw.len() < max_len {
+    return raw.into_iter().collect();
+  };
+  let (l, r) = raw.split_at(max_len / 2);
+  let (_, r) = r.split_at(r.len() - max_len / 2);
+  let mut fmt = String::new();
+  fmt.extend(l);
+  fmt.push('…');
+  fmt.extend(r);
+  fmt
+}
+
+// Reads at most `PARTIAL_HASH_BYTES` from the start of the file and hashes them. Returns the hash alongside the number of bytes actually read, since that's how much progress was made.
+fn process_file_partial(path: &Path, hash_type: HashType) -> Result<(Vec<u8>, u64), String> {
+  let mut hasher = new_hasher(hash_type);
+  let mut file = File::open(path).map_err(|err| format!("failed to open file: {}", err))?;
+  let mut buf = vec![0u8; PARTIAL_HASH_BYTES];
+  let mut read = 0;
+  while read < buf.len() {
+    let n = file
+      .read(&mut buf[read..])
+      .map_err(|err| format!("failed to read file: {}", err))?;
+    if n == 0 {
+      break;
+    };
+    read += n;
+  }
+  hasher.update(&buf[..read]);
+  Ok((hasher.finish(), read as u64))
+}
+
+fn process_file_full(path: &Path, hash_type: HashType) -> Result<Vec<u8>, String> {
+  let mut hasher = new_hasher(hash_type);
   let mut file = File::open(path).map_err(|err| format!("failed to open file: {}", err))?;
   loop {
     let mut buf = vec![0u8; 1024 * 64];
@@ -103,85 +350,208 @@ fn process_file(hashes: Hashes, path: &Path) -> Result<(), String> {
     };
     hasher.update(&buf[..n]);
   }
-  let hash = hasher.finalize().as_bytes().to_vec();
-  hashes.entry(hash).or_default().push(path.to_path_buf());
-  Ok(())
+  Ok(hasher.finish())
 }
 
-pub async fn hash_files_in_trees(folder_paths: &[&Path]) -> Hashes {
-  let Some((Width(term_width), _)) = terminal_size() else {
-    panic!("unable to determine terminal width");
-  };
-
-  let mp = MultiProgress::new();
-  let pb = mp.add(ProgressBar::new(0));
-  pb.set_style(
-    ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})").unwrap().progress_chars("##-")
-  );
-  pb.set_message("Finding files");
-  let (sender, receiver) = unbounded::<(PathBuf, u64)>();
-  let hashes: Hashes = Default::default();
+// Runs `work` for each of `items` across a fixed pool of threads, showing per-thread progress spinners under `mp` and advancing `pb` by the number of bytes each call reports it read. Blocks until every item has been processed.
+fn run_hash_pool<T, R>(
+  mp: &MultiProgress,
+  pb: &ProgressBar,
+  term_width: u16,
+  items: Vec<T>,
+  work: impl Fn(&T) -> Result<(R, u64), String> + Send + Sync + 'static,
+) -> Vec<(T, R)>
+where
+  T: std::fmt::Debug + Send + 'static,
+  R: Send + 'static,
+{
+  let (sender, receiver) = unbounded::<T>();
+  let (result_sender, result_receiver) = unbounded::<(T, R)>();
+  let work = Arc::new(work);
 
   let mut thread_pool = Vec::new();
   for _ in 0..num_cpus::get() {
-    let hashes = hashes.clone();
+    let receiver = receiver.clone();
+    let result_sender = result_sender.clone();
     let mp = mp.clone();
     let pb = pb.clone();
-    let receiver = receiver.clone();
+    let work = work.clone();
     let thread_pb = mp.add(ProgressBar::new_spinner());
     thread_pool.push(spawn(move || {
-      for (path, size) in receiver {
-        {
-          let raw = format!("Processing {:?}", path).chars().collect_vec();
-          // TODO Handle underflow.
-          let max_len = usize::from(term_width) - 15;
-          let mut fmt = String::new();
-          if raw.len() >= max_len {
-            let (l, r) = raw.split_at(max_len / 2);
-            let (_, r) = r.split_at(r.len() - max_len / 2);
-            fmt.extend(l);
-            fmt.push('…');
-            fmt.extend(r);
-          } else {
-            fmt.extend(raw);
-          };
-          thread_pb.set_message(fmt);
-        };
+      for item in receiver {
+        thread_pb.set_message(fit_to_terminal(format!("Processing {:?}", item), term_width));
         thread_pb.tick();
-        if let Err(err) = process_file(hashes.clone(), &path) {
-          mp.println(format!("⚠️ [{:?}] {}", path, err)).unwrap();
+        match work(&item) {
+          Ok((result, bytes_read)) => {
+            pb.inc(bytes_read);
+            result_sender.send((item, result)).unwrap();
+          }
+          Err(err) => {
+            mp.println(format!("⚠️ [{:?}] {}", item, err)).unwrap();
+          }
         };
-        pb.inc(size);
       }
       thread_pb.finish_and_clear();
     }));
   }
   drop(receiver);
+  drop(result_sender);
+
+  for item in items {
+    sender.send(item).unwrap();
+  }
+  drop(sender);
+
+  for t in thread_pool {
+    t.join().unwrap();
+  }
+
+  result_receiver.try_iter().collect()
+}
+
+pub async fn hash_files_in_trees(
+  folder_paths: &[&Path],
+  hash_type: HashType,
+  cache_path: Option<&Path>,
+  filters: Arc<FileFilters>,
+) -> Hashes {
+  let Some((Width(term_width), _)) = terminal_size() else {
+    panic!("unable to determine terminal width");
+  };
+
+  let mp = MultiProgress::new();
+  let pb = mp.add(ProgressBar::new(0));
+  pb.set_style(
+    ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})").unwrap().progress_chars("##-")
+  );
+  pb.set_message("Finding files");
+  let (sender, receiver) = unbounded::<(PathBuf, u64, i64, Option<PathBuf>, Option<Vec<u8>>)>();
+  let hashes: Hashes = Default::default();
+  let cache = cache_path.map(|path| Arc::new(HashCache::load(path.to_path_buf())));
 
   let ctx = Ctx {
     mp: mp.clone(),
-    pb: pb.clone(),
     sender,
+    cache: cache.clone(),
+    hash_type,
+    filters,
   };
 
-  iter(folder_paths)
-    .for_each_concurrent(None, |folder_path| {
-      let ctx = ctx.clone();
-      let mp = mp.clone();
-      async move {
-        if let Err(err) = visit_dir(&ctx, folder_path).await {
-          mp.println(format!("⚠️ [{:?}] {}", folder_path, err))
-            .unwrap();
-        };
-      }
-    })
-    .await;
-  // Drop sender.
+  let walk = iter(folder_paths).for_each_concurrent(None, |folder_path| {
+    let ctx = ctx.clone();
+    let mp = mp.clone();
+    async move {
+      if let Err(err) = visit_dir(&ctx, folder_path).await {
+        mp.println(format!("⚠️ [{:?}] {}", folder_path, err))
+          .unwrap();
+      };
+    }
+  });
+
+  // Stage 1: once the walk has finished, group every candidate it found by size, cache hits included, since a same-sized uncached file can only be declared unique if every same-sized file (cached or not) was accounted for. A size bucket with a single member can never have a duplicate, so it's resolved immediately: straight from the cached hash if there is one, otherwise without reading the file at all.
+  let mut size_groups: SizeGroups = Default::default();
+  walk.await;
   drop(ctx);
+  for (path, size, mtime_nanos, canonical_path, cached_hash) in receiver {
+    size_groups
+      .entry(size)
+      .or_default()
+      .push((path, mtime_nanos, canonical_path, cached_hash));
+  }
 
-  for t in thread_pool {
-    t.join().unwrap();
+  // Sizes with at least one cache hit: an uncached file of one of these sizes can never take the stage-2 "unique partial hash" shortcut, since that shortcut only proves uniqueness among other *uncached* peers, and it may still be a full-content match for a same-sized cached file it was never compared against.
+  let mut cached_sizes: HashSet<u64> = Default::default();
+  let mut partial_candidates = Vec::new();
+  for (size, infos) in size_groups {
+    let unique_size = infos.len() == 1;
+    if infos.iter().any(|(_, _, _, cached_hash)| cached_hash.is_some()) {
+      cached_sizes.insert(size);
+    };
+    for (path, mtime_nanos, canonical_path, cached_hash) in infos {
+      if let Some(hash) = cached_hash {
+        let mut key = vec![KEY_TAG_FULL_HASH];
+        key.extend_from_slice(&hash);
+        hashes.entry(key).or_default().push(path);
+      } else if unique_size {
+        let mut key = vec![KEY_TAG_UNIQUE_SIZE];
+        key.extend_from_slice(&size.to_le_bytes());
+        hashes.entry(key).or_default().push(path);
+      } else {
+        partial_candidates.push((path, size, mtime_nanos, canonical_path));
+      }
+    }
+  }
+
+  // Stage 2: for the remaining same-sized files, hash only the first `PARTIAL_HASH_BYTES` and sub-group by (size, partial hash). A sub-group with a single member still can't collide, so it's resolved without a full read.
+  pb.set_message("Partial hashing");
+  pb.inc_length(
+    partial_candidates
+      .iter()
+      .map(|(_, size, _, _)| (*size).min(PARTIAL_HASH_BYTES as u64))
+      .sum(),
+  );
+  let partial_results = run_hash_pool(
+    &mp,
+    &pb,
+    term_width,
+    partial_candidates,
+    move |(path, _size, _mtime_nanos, _canonical_path)| process_file_partial(path, hash_type),
+  );
+
+  let mut partial_groups: HashMap<
+    (u64, Vec<u8>),
+    Vec<UnhashedInfo>,
+    BuildHasherDefault<FxHasher>,
+  > = Default::default();
+  for ((path, size, mtime_nanos, canonical_path), partial_hash) in partial_results {
+    partial_groups
+      .entry((size, partial_hash))
+      .or_default()
+      .push((path, mtime_nanos, canonical_path));
+  }
+
+  let mut full_candidates = Vec::new();
+  for ((size, partial_hash), infos) in partial_groups {
+    if infos.len() == 1 && !cached_sizes.contains(&size) {
+      let mut key = vec![KEY_TAG_UNIQUE_PARTIAL];
+      key.extend_from_slice(&size.to_le_bytes());
+      key.extend_from_slice(&partial_hash);
+      hashes
+        .entry(key)
+        .or_default()
+        .extend(infos.into_iter().map(|(path, _, _)| path));
+    } else {
+      full_candidates.extend(infos.into_iter().map(|(path, mtime_nanos, canonical_path)| {
+        (path, size, mtime_nanos, canonical_path)
+      }));
+    }
   }
+
+  // Stage 3: only files that still collide on both size and partial hash are worth a full read.
+  pb.set_message("Full hashing");
+  pb.inc_length(full_candidates.iter().map(|(_, size, _, _)| *size).sum());
+  let full_results = run_hash_pool(
+    &mp,
+    &pb,
+    term_width,
+    full_candidates,
+    move |(path, size, _mtime_nanos, _canonical_path)| Ok((process_file_full(path, hash_type)?, *size)),
+  );
+  for ((path, size, mtime_nanos, canonical_path), hash) in full_results {
+    if let (Some(cache), Some(canonical_path)) = (&cache, canonical_path) {
+      cache.put(canonical_path, size, mtime_nanos, hash_type, hash.clone());
+    };
+    let mut key = vec![KEY_TAG_FULL_HASH];
+    key.extend_from_slice(&hash);
+    hashes.entry(key).or_default().push(path);
+  }
+
+  if let Some(cache) = cache {
+    if let Ok(cache) = Arc::try_unwrap(cache) {
+      cache.flush();
+    };
+  };
+
   pb.finish_and_clear();
   mp.clear().unwrap();
 